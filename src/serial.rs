@@ -0,0 +1,95 @@
+use core::fmt;
+use lazy_static::lazy_static;
+use spin;
+use x86_64::instructions::port::Port;
+
+const SERIAL_PORT: u16 = 0x3F8;
+
+lazy_static! {
+    pub static ref SERIAL1: spin::Mutex<SerialPort> = spin::Mutex::new(SerialPort::new(SERIAL_PORT));
+}
+
+/// A minimal driver for the 16550 UART, enough to push raw bytes out over
+/// the serial line so host tooling (e.g. `cargo test` under QEMU) can read
+/// them from stdout.
+pub struct SerialPort {
+    data: Port<u8>,
+    interrupt_enable: Port<u8>,
+    fifo_control: Port<u8>,
+    line_control: Port<u8>,
+    line_status: Port<u8>,
+}
+
+impl SerialPort {
+    fn new(base: u16) -> SerialPort {
+        let mut port = SerialPort {
+            data: Port::new(base),
+            interrupt_enable: Port::new(base + 1),
+            fifo_control: Port::new(base + 2),
+            line_control: Port::new(base + 3),
+            line_status: Port::new(base + 5),
+        };
+        port.init();
+        port
+    }
+
+    fn init(&mut self) {
+        unsafe {
+            // Disable interrupts.
+            self.interrupt_enable.write(0x00);
+            // Enable DLAB to set the baud rate divisor.
+            self.line_control.write(0x80);
+            // Divisor low/high byte for a baud rate of 38400.
+            self.data.write(0x03);
+            self.interrupt_enable.write(0x00);
+            // 8 bits, no parity, one stop bit; also clears DLAB.
+            self.line_control.write(0x03);
+            // Enable FIFO, clear them, with 14-byte threshold.
+            self.fifo_control.write(0xC7);
+        }
+    }
+
+    fn line_sts(&mut self) -> u8 {
+        unsafe { self.line_status.read() }
+    }
+
+    fn send(&mut self, byte: u8) {
+        // Busy-wait until the transmit-holding register is empty (bit 5).
+        while self.line_sts() & 0x20 == 0 {}
+        unsafe {
+            self.data.write(byte);
+        }
+    }
+}
+
+impl fmt::Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.send(byte);
+        }
+        Ok(())
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        SERIAL1.lock().write_fmt(args).unwrap();
+    });
+}
+
+/// Prints to the host through the serial interface, without a trailing newline.
+#[macro_export]
+macro_rules! serial_print {
+    ($($arg:tt)*) => ($crate::serial::_print(format_args!($($arg)*)))
+}
+
+/// Prints to the host through the serial interface, appending a newline.
+#[macro_export]
+macro_rules! serial_println {
+    () => ($crate::serial_print!("\n"));
+    ($($arg:tt)*) => ($crate::serial_print!("{}\n", format_args!($($arg)*)));
+}
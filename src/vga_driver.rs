@@ -2,10 +2,29 @@ use volatile::Volatile;
 use core::fmt;
 use lazy_static::lazy_static;
 use spin;
+use x86_64::instructions::port::Port;
+
+const CURSOR_INDEX_PORT: u16 = 0x3D4;
+const CURSOR_DATA_PORT: u16 = 0x3D5;
 
 const SCREEN_HEIGHT: usize = 25;
 const SCREEN_WIDTH: usize = 80;
 
+const ESC: u8 = 0x1b;
+const MAX_ANSI_PARAMS: usize = 4;
+
+/// Where `write_string` is in parsing an ANSI SGR escape sequence
+/// (`ESC [ <params> m`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AnsiState {
+    /// Not in an escape sequence; bytes are written to the screen as-is.
+    Normal,
+    /// Just saw `ESC`, waiting for the `[` that starts a CSI sequence.
+    Escape,
+    /// Inside `ESC [`, collecting `;`-separated numeric parameters.
+    Params,
+}
+
 // Lazy variable are initialized at compile time, in contrast to normal variables
 // that are initialized at run time
 lazy_static! {
@@ -14,6 +33,10 @@ lazy_static! {
         row_pos: 0,
         color_code: ColorCode::new(Color::Yellow, Color::Black),
         buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
+        ansi_state: AnsiState::Normal,
+        ansi_params: [0; MAX_ANSI_PARAMS],
+        ansi_param_count: 0,
+        ansi_current_param: 0,
     });
 }
 
@@ -23,10 +46,16 @@ pub struct VgaWriter {
     row_pos: usize,
     color_code: ColorCode,
     buffer: &'static mut Buffer,
+    ansi_state: AnsiState,
+    ansi_params: [u16; MAX_ANSI_PARAMS],
+    ansi_param_count: usize,
+    ansi_current_param: u16,
 }
 
 impl VgaWriter {
-    fn write_byte(&mut self, text_as_byte: u8) {
+    /// Writes a single byte to the screen, handling `\n` and scrolling.
+    /// Does not look at ANSI escape state; callers go through `write_byte`.
+    fn emit_byte(&mut self, text_as_byte: u8) {
         match text_as_byte {
             b'\n' => self.new_line(),
             text_as_byte => {
@@ -46,6 +75,92 @@ impl VgaWriter {
                 self.col_pos += 1;
             }
         }
+
+        self.update_cursor();
+    }
+
+    fn write_byte(&mut self, text_as_byte: u8) {
+        match self.ansi_state {
+            AnsiState::Normal => {
+                if text_as_byte == ESC {
+                    self.ansi_state = AnsiState::Escape;
+                } else {
+                    self.emit_byte(text_as_byte);
+                }
+            }
+            AnsiState::Escape => {
+                if text_as_byte == b'[' {
+                    self.ansi_state = AnsiState::Params;
+                    self.ansi_param_count = 0;
+                    self.ansi_current_param = 0;
+                } else {
+                    // Not a CSI sequence after all; drop the escape and
+                    // re-dispatch the byte (through write_byte, not
+                    // emit_byte, so it can itself start a new escape).
+                    self.ansi_state = AnsiState::Normal;
+                    self.write_byte(text_as_byte);
+                }
+            }
+            AnsiState::Params => self.write_ansi_param_byte(text_as_byte),
+        }
+    }
+
+    fn write_ansi_param_byte(&mut self, text_as_byte: u8) {
+        match text_as_byte {
+            b'0'..=b'9' => {
+                if self.ansi_current_param >= 100 {
+                    // More than 3 digits isn't a valid SGR parameter; bail out.
+                    // Re-dispatch through write_byte (not emit_byte) so this
+                    // byte can itself start a new escape sequence.
+                    self.ansi_state = AnsiState::Normal;
+                    self.write_byte(text_as_byte);
+                    return;
+                }
+                self.ansi_current_param = self.ansi_current_param * 10 + (text_as_byte - b'0') as u16;
+            }
+            b';' => {
+                if self.ansi_param_count >= MAX_ANSI_PARAMS {
+                    self.ansi_state = AnsiState::Normal;
+                    self.write_byte(text_as_byte);
+                    return;
+                }
+                self.ansi_params[self.ansi_param_count] = self.ansi_current_param;
+                self.ansi_param_count += 1;
+                self.ansi_current_param = 0;
+            }
+            b'm' => {
+                if self.ansi_param_count < MAX_ANSI_PARAMS {
+                    self.ansi_params[self.ansi_param_count] = self.ansi_current_param;
+                    self.ansi_param_count += 1;
+                }
+                self.apply_sgr_params();
+                self.ansi_state = AnsiState::Normal;
+            }
+            _ => {
+                // Malformed sequence; abort parsing and re-dispatch this byte
+                // through write_byte so it can itself start a new escape.
+                self.ansi_state = AnsiState::Normal;
+                self.write_byte(text_as_byte);
+            }
+        }
+    }
+
+    /// Applies the SGR parameters collected so far to `self.color_code`.
+    fn apply_sgr_params(&mut self) {
+        for i in 0..self.ansi_param_count {
+            match self.ansi_params[i] {
+                0 => self.color_code = ColorCode::new(Color::Yellow, Color::Black),
+                code @ 30..=37 => self.set_color(ansi_color(code as u8 - 30, false), self.color_code.background()),
+                code @ 90..=97 => self.set_color(ansi_color(code as u8 - 90, true), self.color_code.background()),
+                code @ 40..=47 => self.set_color(self.color_code.foreground(), ansi_color(code as u8 - 40, false)),
+                // 100-107 (bright background) has no usable VGA encoding: the
+                // background only has 3 bits, and the 4th is reserved for
+                // blink (see `ColorCode::with_blink`), so it's ignored rather
+                // than silently aliasing onto the dim 40-47 background.
+                100..=107 => {}
+                _ => {} // unsupported SGR code; ignore
+            }
+        }
     }
 
     pub fn write_string(&mut self, string: &str) {
@@ -53,7 +168,7 @@ impl VgaWriter {
             //     VGA text only support ascii, rust string are utf-8
             //     so they might contain bytes that are not supported by VGA buffer
             match byte {
-                0x20..=0x7e | b'\n' => self.write_byte(byte),
+                ESC | 0x20..=0x7e | b'\n' => self.write_byte(byte),
                 // handle byte not ascii
                 _ => self.write_byte(0xfe)
             }
@@ -61,7 +176,18 @@ impl VgaWriter {
     }
 
     fn new_line(&mut self) {
-        self.row_pos += 1;
+        if self.row_pos < SCREEN_HEIGHT - 1 {
+            self.row_pos += 1;
+        } else {
+            for row in 0..SCREEN_HEIGHT - 1 {
+                for col in 0..SCREEN_WIDTH {
+                    let ch = self.buffer.chars[row + 1][col].read();
+                    self.buffer.chars[row][col].write(ch);
+                }
+            }
+            self.clear_row(SCREEN_HEIGHT - 1);
+        }
+
         self.col_pos = 0;
     }
 
@@ -75,6 +201,56 @@ impl VgaWriter {
             self.buffer.chars[row][col].write(blank);
         }
     }
+
+    /// Sets the color used for subsequent writes, keeping the current blink state.
+    /// A bright `background` (8-15) is clamped to its dim equivalent; see `ColorCode::new`.
+    pub fn set_color(&mut self, foreground: Color, background: Color) {
+        let blinking = self.color_code.is_blinking();
+        self.color_code = ColorCode::new(foreground, background).with_blink(blinking);
+    }
+
+    /// Enables or disables the blink bit for subsequent writes.
+    pub fn set_blink(&mut self, blink: bool) {
+        self.color_code = self.color_code.with_blink(blink);
+    }
+
+    /// Temporarily switches to `foreground`/`background` for the duration of `f`,
+    /// then restores whatever color was active before.
+    pub fn with_color<F: FnOnce(&mut VgaWriter)>(&mut self, foreground: Color, background: Color, f: F) {
+        let previous = self.color_code;
+        self.set_color(foreground, background);
+        f(self);
+        self.color_code = previous;
+    }
+
+    fn write_cursor_register(&mut self, index: u8, value: u8) {
+        let mut index_port: Port<u8> = Port::new(CURSOR_INDEX_PORT);
+        let mut data_port: Port<u8> = Port::new(CURSOR_DATA_PORT);
+
+        unsafe {
+            index_port.write(index);
+            data_port.write(value);
+        }
+    }
+
+    /// Moves the blinking hardware cursor to `row_pos`/`col_pos`.
+    pub fn update_cursor(&mut self) {
+        let pos = (self.row_pos * SCREEN_WIDTH + self.col_pos) as u16;
+
+        self.write_cursor_register(0x0E, (pos >> 8) as u8);
+        self.write_cursor_register(0x0F, (pos & 0xff) as u8);
+    }
+
+    /// Shows the hardware cursor, spanning scanlines 13-15 of the character cell.
+    pub fn enable_cursor(&mut self) {
+        self.write_cursor_register(0x0A, 0x0D);
+        self.write_cursor_register(0x0B, 0x0F);
+    }
+
+    /// Hides the hardware cursor (sets the disable bit in the cursor-start register).
+    pub fn disable_cursor(&mut self) {
+        self.write_cursor_register(0x0A, 0x20);
+    }
 }
 
 impl fmt::Write for VgaWriter {
@@ -89,9 +265,41 @@ impl fmt::Write for VgaWriter {
 struct ColorCode(u8);
 
 impl ColorCode {
+    /// The background field only has 3 usable bits (bits 4-6 of the color
+    /// byte); bit 7 is reserved for blink (see `with_blink`). Bright
+    /// backgrounds (color codes 8-15) are clamped to their dim equivalent
+    /// so they can't silently collide with the blink bit.
     fn new(foreground: Color, background: Color) -> ColorCode {
+        let background = Color::from_code(background as u8 & 0x07);
         ColorCode((background as u8) << 4 | (foreground as u8))
     }
+
+    /// Same as `new`, but also sets the top background bit (bit 7 of the color
+    /// byte, bit 15 of the screen cell) to make the character blink.
+    #[allow(dead_code)]
+    fn new_blinking(foreground: Color, background: Color) -> ColorCode {
+        ColorCode::new(foreground, background).with_blink(true)
+    }
+
+    fn is_blinking(self) -> bool {
+        self.0 & 0b1000_0000 != 0
+    }
+
+    fn with_blink(self, blink: bool) -> ColorCode {
+        if blink {
+            ColorCode(self.0 | 0b1000_0000)
+        } else {
+            ColorCode(self.0 & 0b0111_1111)
+        }
+    }
+
+    fn foreground(self) -> Color {
+        Color::from_code(self.0 & 0x0f)
+    }
+
+    fn background(self) -> Color {
+        Color::from_code((self.0 >> 4) & 0x07)
+    }
 }
 
 // repr transparent make Buffer ins has same memory layout as chars field
@@ -171,13 +379,68 @@ pub enum Color {
     White = 15,
 }
 
+impl Color {
+    fn from_code(code: u8) -> Color {
+        match code {
+            0 => Color::Black,
+            1 => Color::Blue,
+            2 => Color::Green,
+            3 => Color::Cyan,
+            4 => Color::Red,
+            5 => Color::Magenta,
+            6 => Color::Brown,
+            7 => Color::LightGray,
+            8 => Color::DarkGray,
+            9 => Color::LightBlue,
+            10 => Color::LightGreen,
+            11 => Color::LightCyan,
+            12 => Color::LightRed,
+            13 => Color::Pink,
+            14 => Color::Yellow,
+            _ => Color::White,
+        }
+    }
+}
+
+/// Maps an ANSI SGR color code (0-7, the part after 30/40/90/100) to the
+/// matching VGA `Color`, following the standard CGA/ANSI correspondence
+/// (e.g. ANSI 3 "yellow" is VGA `Brown`, its bright variant is VGA `Yellow`).
+fn ansi_color(code: u8, bright: bool) -> Color {
+    match (code, bright) {
+        (0, false) => Color::Black,
+        (1, false) => Color::Red,
+        (2, false) => Color::Green,
+        (3, false) => Color::Brown,
+        (4, false) => Color::Blue,
+        (5, false) => Color::Magenta,
+        (6, false) => Color::Cyan,
+        (7, false) => Color::LightGray,
+        (0, true) => Color::DarkGray,
+        (1, true) => Color::LightRed,
+        (2, true) => Color::LightGreen,
+        (3, true) => Color::Yellow,
+        (4, true) => Color::LightBlue,
+        (5, true) => Color::Pink,
+        (6, true) => Color::LightCyan,
+        (7, true) => Color::White,
+        _ => Color::White,
+    }
+}
+
 // fmt:Arguments represent can multiple argument
 // Since the macros need to be able to call _print from outside of the module,
 // the function needs to be public.
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
     use core::fmt::Write;
-    VGA_WRITER.lock().write_fmt(args).unwrap();
+    use x86_64::instructions::interrupts;
+
+    // Disable interrupts while the lock is held so an interrupt handler that
+    // also wants to print can't fire on the interrupted code and deadlock on
+    // the mutex.
+    interrupts::without_interrupts(|| {
+        VGA_WRITER.lock().write_fmt(args).unwrap();
+    });
 }
 
 // The #[macro_export] attribute makes the macro available to the whole crate (not just the module it is defined) and external crates
@@ -197,4 +460,140 @@ macro_rules! print {
 macro_rules! println {
     () => ($crate::print!("\n"));
     ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
+
+#[test_case]
+fn test_new_line_scrolls_rows_up_and_blanks_last_row() {
+    let mut writer = VGA_WRITER.lock();
+
+    // Give every row a distinct, recognizable marker character.
+    for row in 0..SCREEN_HEIGHT {
+        let marker = b'a' + (row % 26) as u8;
+        for col in 0..SCREEN_WIDTH {
+            writer.buffer.chars[row][col].write(ScreenChar {
+                ascii_char: marker,
+                color_code: writer.color_code,
+            });
+        }
+    }
+
+    writer.row_pos = SCREEN_HEIGHT - 1;
+    writer.col_pos = 5;
+    writer.new_line();
+
+    // Row 0 should now hold what used to be row 1's marker.
+    let expected_marker = b'a' + (1 % 26) as u8;
+    for col in 0..SCREEN_WIDTH {
+        let screen_char = writer.buffer.chars[0][col].read();
+        assert_eq!(screen_char.ascii_char, expected_marker);
+    }
+
+    // The bottom row is freshly scrolled in, so it should be blank.
+    for col in 0..SCREEN_WIDTH {
+        let screen_char = writer.buffer.chars[SCREEN_HEIGHT - 1][col].read();
+        assert_eq!(screen_char.ascii_char, b' ');
+    }
+
+    // row_pos stays pinned at the last row instead of running past it.
+    assert_eq!(writer.row_pos, SCREEN_HEIGHT - 1);
+    assert_eq!(writer.col_pos, 0);
+}
+
+/// Resets a writer to a known baseline (top-left, default yellow-on-black,
+/// no blink) so ANSI tests don't depend on whatever ran before them.
+fn reset_for_ansi_test(writer: &mut VgaWriter) {
+    writer.row_pos = 0;
+    writer.col_pos = 0;
+    writer.color_code = ColorCode::new(Color::Yellow, Color::Black);
+}
+
+#[test_case]
+fn test_ansi_reset_code_restores_default_color() {
+    let mut writer = VGA_WRITER.lock();
+    reset_for_ansi_test(&mut writer);
+
+    writer.write_string("\x1b[31m\x1b[0m");
+
+    assert_eq!(writer.color_code, ColorCode::new(Color::Yellow, Color::Black));
+}
+
+#[test_case]
+fn test_ansi_sets_foreground_and_background() {
+    let mut writer = VGA_WRITER.lock();
+    reset_for_ansi_test(&mut writer);
+
+    writer.write_string("\x1b[31;44m");
+
+    assert_eq!(writer.color_code.foreground(), Color::Red);
+    assert_eq!(writer.color_code.background(), Color::Blue);
+}
+
+#[test_case]
+fn test_ansi_malformed_byte_aborts_and_prints_literally() {
+    let mut writer = VGA_WRITER.lock();
+    reset_for_ansi_test(&mut writer);
+
+    // 'x' is neither a digit, ';' nor 'm', so parsing should abort and both
+    // 'x' and the trailing 'm' should be printed as plain characters.
+    writer.write_string("\x1b[3xm");
+
+    assert_eq!(writer.buffer.chars[0][0].read().ascii_char, b'x');
+    assert_eq!(writer.buffer.chars[0][1].read().ascii_char, b'm');
+    assert_eq!(writer.color_code, ColorCode::new(Color::Yellow, Color::Black));
+}
+
+#[test_case]
+fn test_ansi_digit_overflow_aborts_and_prints_literally() {
+    let mut writer = VGA_WRITER.lock();
+    reset_for_ansi_test(&mut writer);
+
+    // A 4th digit overflows the 3-digit parameter limit; parsing should
+    // abort and print the offending digit and the trailing 'm' literally.
+    writer.write_string("\x1b[9999m");
+
+    assert_eq!(writer.buffer.chars[0][0].read().ascii_char, b'9');
+    assert_eq!(writer.buffer.chars[0][1].read().ascii_char, b'm');
+    assert_eq!(writer.color_code, ColorCode::new(Color::Yellow, Color::Black));
+}
+
+#[test_case]
+fn test_ansi_too_many_params_aborts_and_prints_literally() {
+    let mut writer = VGA_WRITER.lock();
+    reset_for_ansi_test(&mut writer);
+
+    // Only MAX_ANSI_PARAMS (4) parameters are supported; the 5th ';'
+    // (after four params have already been stored) should abort parsing
+    // and the rest comes through as plain characters.
+    writer.write_string("\x1b[1;2;3;4;5;6m");
+
+    assert_eq!(writer.buffer.chars[0][0].read().ascii_char, b';');
+    assert_eq!(writer.buffer.chars[0][1].read().ascii_char, b'6');
+    assert_eq!(writer.buffer.chars[0][2].read().ascii_char, b'm');
+    assert_eq!(writer.color_code, ColorCode::new(Color::Yellow, Color::Black));
+}
+
+#[test_case]
+fn test_ansi_abort_byte_can_itself_start_a_new_escape() {
+    let mut writer = VGA_WRITER.lock();
+    reset_for_ansi_test(&mut writer);
+
+    // "999" is a valid 3-digit parameter, but it's immediately followed by a
+    // fresh ESC instead of ';' or 'm'. That ESC must restart escape parsing
+    // (not get printed as a raw control byte), so "\x1b[31m" still lands.
+    writer.write_string("\x1b[999\x1b[31m");
+
+    assert_eq!(writer.col_pos, 0, "the aborted sequence should not have printed anything");
+    assert_eq!(writer.color_code.foreground(), Color::Red);
+}
+
+#[test_case]
+fn test_ansi_bright_background_codes_are_ignored_not_aliased() {
+    let mut writer = VGA_WRITER.lock();
+    reset_for_ansi_test(&mut writer);
+
+    writer.write_string("\x1b[101m");
+
+    // 101 (bright red background) has no usable VGA encoding, so it must be
+    // ignored rather than silently aliasing onto the dim 41 (red) background.
+    assert_eq!(writer.color_code.background(), Color::Black);
 }
\ No newline at end of file